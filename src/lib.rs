@@ -0,0 +1,322 @@
+//! Read and de-duplicate entries from a Redis server's SLOWLOG.
+//!
+//! The binary is a thin wrapper over this crate; other programs can embed
+//! slowlog monitoring by calling [`server_version`] and [`fetch_slowlogs`]
+//! directly, or by driving a [`SlowlogStream`] to receive only entries they
+//! have not seen before.
+
+#[macro_use]
+extern crate lazy_static;
+
+use chrono::DateTime;
+use redis::FromRedisValue;
+use redis::InfoDict;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+lazy_static! {
+    static ref IGNORE_COMMANDS: Vec<&'static str> = vec!["SLOWLOG", "INFO"];
+}
+
+#[derive(Default, Debug)]
+pub struct RedisVersion {
+    pub major: usize,
+    pub minor: usize,
+    pub patch: usize,
+}
+
+/// Query the server for its reported `redis_version`. The outer `Result`
+/// surfaces connection/`INFO` failures so callers can retry; the inner
+/// `Option` is `None` when the reply carries no version string.
+pub fn server_version(conn: &mut redis::Connection) -> Result<Option<RedisVersion>, redis::RedisError> {
+    let info: InfoDict = redis::cmd("INFO").arg("server").query(conn)?;
+    let version_str: String = info.get("redis_version").unwrap_or_default();
+    Ok(parse_version(&version_str))
+}
+
+/// Parse an `X.Y.Z` version string, returning `None` for anything that does
+/// not match (empty replies, `X.Y`, fork builds like `6.2.6-rc1`, …) so a
+/// malformed `INFO` reply never panics the caller.
+fn parse_version(version_str: &str) -> Option<RedisVersion> {
+    let mut parts = version_str.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next()?.parse().ok()?;
+    Some(RedisVersion {
+        major,
+        minor,
+        patch,
+    })
+}
+
+#[derive(Default, Debug, Serialize)]
+pub struct Slowlog {
+    pub id: u64,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: u64,
+    #[serde(serialize_with = "serialize_exec_time")]
+    pub exec_time: Duration,
+    pub cmd: Vec<String>,
+    pub address: String,     // support by Redis 4.0 or greater
+    pub client_name: String, // support by Redis 4.0 or greater
+}
+
+/// Render a unix timestamp (seconds) as an ISO-8601 / RFC-3339 string.
+pub fn iso8601(timestamp: u64) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+fn serialize_timestamp<S>(timestamp: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&iso8601(*timestamp))
+}
+
+fn serialize_exec_time<S>(exec_time: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_u64(exec_time.as_micros() as u64)
+}
+
+/// Parse a single raw `SLOWLOG GET` entry. Redis 4.0 and greater return a
+/// six-field reply (with client address and name); earlier versions return a
+/// four-field reply, so the wider shape is tried first and the narrower one
+/// used as a fallback.
+fn parse_slowlog(value: &redis::Value) -> Result<Slowlog, redis::RedisError> {
+    if let Ok(s) = <(u64, u64, u64, Vec<String>, String, String)>::from_redis_value(value) {
+        Ok(Slowlog {
+            id: s.0,
+            timestamp: s.1,
+            exec_time: Duration::from_micros(s.2),
+            cmd: s.3,
+            address: s.4,
+            client_name: s.5,
+        })
+    } else {
+        let s: (u64, u64, u64, Vec<String>) = FromRedisValue::from_redis_value(value)?;
+        Ok(Slowlog {
+            id: s.0,
+            timestamp: s.1,
+            exec_time: Duration::from_micros(s.2),
+            cmd: s.3,
+            ..Slowlog::default()
+        })
+    }
+}
+
+/// Fetch up to `count` slowlog entries, dropping the tool's own bookkeeping
+/// commands (see [`IGNORE_COMMANDS`]).
+pub fn fetch_slowlogs(conn: &mut redis::Connection, count: usize) -> Result<Vec<Slowlog>, redis::RedisError> {
+    let raw_slowlogs: Vec<redis::Value> = redis::cmd("SLOWLOG")
+        .arg("GET")
+        .arg(format!("{}", count))
+        .query(conn)?;
+    let mut slowlogs: Vec<Slowlog> = vec![];
+    for raw_slowlog in raw_slowlogs.iter() {
+        let slowlog = parse_slowlog(raw_slowlog)?;
+        if let Some(head) = slowlog.cmd.first() {
+            if IGNORE_COMMANDS.contains(&head.to_uppercase().as_str()) {
+                continue;
+            }
+        }
+        slowlogs.push(slowlog);
+    }
+    Ok(slowlogs)
+}
+
+/// Intrusive doubly-linked-list node, referenced by slot index.
+struct Node {
+    id: u64,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Fixed-capacity set of recently seen slowlog ids used to suppress
+/// duplicate output. Because SLOWLOG is a bounded ring on the server,
+/// keeping a few multiples of `slowlog-max-len` ids is enough to dedup
+/// correctly while keeping memory constant.
+pub struct SeenIds {
+    capacity: usize,
+    index: HashMap<u64, usize>, // id -> slot in `nodes`
+    nodes: Vec<Node>,
+    head: Option<usize>, // most recently used
+    tail: Option<usize>, // least recently used
+    free: Vec<usize>,    // slots freed by eviction, ready for reuse
+}
+
+impl SeenIds {
+    pub fn new(capacity: usize) -> SeenIds {
+        SeenIds {
+            capacity,
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            head: None,
+            tail: None,
+            free: Vec::new(),
+        }
+    }
+
+    /// Mark `id` as most-recently-used. Returns `true` if the id had already
+    /// been seen, `false` if this is the first time (and it is now recorded,
+    /// evicting the least-recently-used id when capacity is exceeded).
+    ///
+    /// All paths are O(1): the index map locates an existing node in constant
+    /// time and recency is maintained by splicing list pointers rather than
+    /// scanning.
+    pub fn mark(&mut self, id: u64) -> bool {
+        if let Some(&slot) = self.index.get(&id) {
+            self.detach(slot);
+            self.push_front(slot);
+            return true;
+        }
+        let slot = self.alloc(id);
+        self.index.insert(id, slot);
+        self.push_front(slot);
+        if self.index.len() > self.capacity {
+            if let Some(tail) = self.tail {
+                let evicted = self.nodes[tail].id;
+                self.detach(tail);
+                self.index.remove(&evicted);
+                self.free.push(tail);
+            }
+        }
+        false
+    }
+
+    fn alloc(&mut self, id: u64) -> usize {
+        let node = Node {
+            id,
+            prev: None,
+            next: None,
+        };
+        if let Some(slot) = self.free.pop() {
+            self.nodes[slot] = node;
+            slot
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+
+    fn detach(&mut self, slot: usize) {
+        let (prev, next) = (self.nodes[slot].prev, self.nodes[slot].next);
+        match prev {
+            Some(p) => self.nodes[p].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.nodes[n].prev = prev,
+            None => self.tail = prev,
+        }
+        self.nodes[slot].prev = None;
+        self.nodes[slot].next = None;
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        self.nodes[slot].prev = None;
+        self.nodes[slot].next = self.head;
+        if let Some(h) = self.head {
+            self.nodes[h].prev = Some(slot);
+        }
+        self.head = Some(slot);
+        if self.tail.is_none() {
+            self.tail = Some(slot);
+        }
+    }
+}
+
+/// Polls a connection for slowlog entries, yielding only the entries whose id
+/// has not been returned before.
+pub struct SlowlogStream<'a> {
+    conn: &'a mut redis::Connection,
+    count: usize,
+    seen: SeenIds,
+}
+
+impl<'a> SlowlogStream<'a> {
+    pub fn new(conn: &'a mut redis::Connection, count: usize, capacity: usize) -> SlowlogStream<'a> {
+        SlowlogStream {
+            conn,
+            count,
+            seen: SeenIds::new(capacity),
+        }
+    }
+
+    /// Fetch the current slowlog and return only the not-yet-seen entries.
+    pub fn poll(&mut self) -> Result<Vec<Slowlog>, redis::RedisError> {
+        let mut fresh: Vec<Slowlog> = vec![];
+        for slowlog in fetch_slowlogs(&mut *self.conn, self.count)? {
+            if !self.seen.mark(slowlog.id) {
+                fresh.push(slowlog);
+            }
+        }
+        Ok(fresh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+
+    #[test]
+    fn parses_six_field_entry() {
+        let raw = Value::Bulk(vec![
+            Value::Int(7),
+            Value::Int(1_500_000_000),
+            Value::Int(1234),
+            Value::Bulk(vec![
+                Value::Data(b"GET".to_vec()),
+                Value::Data(b"key".to_vec()),
+            ]),
+            Value::Data(b"127.0.0.1:6379".to_vec()),
+            Value::Data(b"worker".to_vec()),
+        ]);
+        let s = parse_slowlog(&raw).unwrap();
+        assert_eq!(s.id, 7);
+        assert_eq!(s.exec_time, Duration::from_micros(1234));
+        assert_eq!(s.cmd, vec!["GET".to_string(), "key".to_string()]);
+        assert_eq!(s.address, "127.0.0.1:6379");
+        assert_eq!(s.client_name, "worker");
+    }
+
+    #[test]
+    fn parses_four_field_entry() {
+        let raw = Value::Bulk(vec![
+            Value::Int(3),
+            Value::Int(1_500_000_000),
+            Value::Int(42),
+            Value::Bulk(vec![Value::Data(b"PING".to_vec())]),
+        ]);
+        let s = parse_slowlog(&raw).unwrap();
+        assert_eq!(s.id, 3);
+        assert_eq!(s.cmd, vec!["PING".to_string()]);
+        assert!(s.address.is_empty());
+        assert!(s.client_name.is_empty());
+    }
+
+    #[test]
+    fn parse_version_rejects_malformed() {
+        assert!(parse_version("").is_none());
+        assert!(parse_version("7.4").is_none());
+        assert!(parse_version("6.2.6-rc1").is_none());
+        let v = parse_version("6.2.6").unwrap();
+        assert_eq!((v.major, v.minor, v.patch), (6, 2, 6));
+    }
+
+    #[test]
+    fn seen_ids_evicts_least_recently_used() {
+        let mut seen = SeenIds::new(2);
+        assert!(!seen.mark(1));
+        assert!(!seen.mark(2));
+        assert!(seen.mark(1)); // 1 is now most-recently-used
+        assert!(!seen.mark(3)); // evicts 2, the least-recently-used
+        assert!(seen.mark(1)); // 1 is still tracked
+        assert!(!seen.mark(2)); // 2 was evicted, so it looks fresh again
+    }
+}
@@ -1,128 +1,234 @@
-#[macro_use]
-extern crate lazy_static;
+use akame::{fetch_slowlogs, iso8601, server_version, SeenIds, Slowlog};
+use chrono::DateTime;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use redis::FromRedisValue;
-use redis::InfoDict;
-use std::collections::HashMap;
-use std::time::Duration;
+/// Initial reconnect delay, doubled on each consecutive failure.
+const BACKOFF_INITIAL_MS: u64 = 100;
+/// Upper bound for the reconnect delay before jitter, which adds up to ~25%.
+const BACKOFF_CAP_MS: u64 = 30_000;
+/// In one-shot mode, give up after this many failed connection attempts so the
+/// tool fails fast in scripts and cron jobs instead of hanging forever.
+const CONNECT_MAX_ATTEMPTS: u32 = 5;
 
-lazy_static! {
-    static ref IGNORE_COMMANDS: Vec<&'static str> = vec!["SLOWLOG", "INFO"];
-}
+/// Tail the SLOWLOG of one or more running Redis servers.
+#[derive(Parser, Debug, Clone)]
+#[clap(name = "akame", about = "redis slowlog monitor")]
+struct Config {
+    /// Redis instance as `host:port`; repeat or comma-separate for a fleet.
+    #[clap(short = 'H', long = "host", value_delimiter = ',', default_value = "127.0.0.1:6379")]
+    hosts: Vec<String>,
+
+    /// Redis database number.
+    #[clap(short, long, default_value_t = 0)]
+    db: usize,
+
+    /// Redis password used for AUTH.
+    #[clap(short = 'a', long)]
+    password: Option<String>,
+
+    /// Poll interval in milliseconds.
+    #[clap(short, long, default_value_t = 5000)]
+    interval: u64,
+
+    /// Number of slowlog entries to fetch per poll.
+    #[clap(short, long, default_value_t = 100)]
+    count: usize,
 
-#[derive(Default, Debug)]
-struct RedisVersion {
-    major: usize,
-    minor: usize,
-    patch: usize,
+    /// Connection timeout in milliseconds.
+    #[clap(short, long, default_value_t = 5000)]
+    timeout: u64,
+
+    /// Maximum number of slowlog ids kept for deduplication.
+    #[clap(long = "lru-capacity", default_value_t = 4096)]
+    lru_capacity: usize,
+
+    /// Keep polling instead of exiting after a single pass.
+    #[clap(short, long)]
+    follow: bool,
+
+    /// Output format.
+    #[clap(long, value_enum, default_value = "text")]
+    format: Format,
 }
 
-fn get_version(conn: &redis::Connection) -> Option<RedisVersion> {
-    let info: InfoDict = redis::cmd("INFO")
-        .arg("server")
-        .query(conn)
-        .expect("fail info command");
-    let version_str = info.get("redis_version").unwrap_or_else(|| "".to_string());
-    if version_str.is_empty() {
-        None
-    } else {
-        let v: Vec<&str> = version_str.split('.').collect();
-        let version = RedisVersion {
-            major: v[0].parse::<usize>().expect("invalid major version"),
-            minor: v[1].parse::<usize>().expect("invalid minor version"),
-            patch: v[2].parse::<usize>().expect("invalid patch version"),
-        };
-        Some(version)
+impl Config {
+    fn url_for(&self, host: &str) -> String {
+        match self.password {
+            Some(ref pw) => format!("redis://:{}@{}/{}", pw, host, self.db),
+            None => format!("redis://{}/{}", host, self.db),
+        }
     }
 }
 
-#[derive(Default, Debug)]
-struct RedisSlowlog {
-    id: u64,
-    timestamp: u64,
-    exec_time: Duration,
-    cmd: Vec<String>,
-    address: String,     // support by Redis 4.0 or greater
-    client_name: String, // support by Redis 4.0 or greater
+/// How each slowlog entry is rendered on stdout.
+#[derive(Clone, Debug, ValueEnum)]
+enum Format {
+    /// Human-readable single line per entry.
+    Text,
+    /// One JSON object per line (JSONL).
+    Json,
+    /// Comma-separated values with a leading header row.
+    Csv,
 }
 
-fn get_slowlogs(conn: &redis::Connection, num: usize, version: usize) -> Vec<RedisSlowlog> {
-    let mut slowlogs: Vec<RedisSlowlog> = vec![];
-    let raw_slowlogs: Vec<redis::Value> = redis::cmd("SLOWLOG")
-        .arg("GET")
-        .arg(format!("{}", num))
-        .query(conn)
-        .expect("fail slowlog command");
-    for raw_slowlog in raw_slowlogs.iter() {
-        let slowlog = if version >= 4 {
-            let s: (u64, u64, u64, Vec<String>, String, String) =
-                FromRedisValue::from_redis_value(raw_slowlog).unwrap();
-            RedisSlowlog {
-                id: s.0,
-                timestamp: s.1,
-                exec_time: Duration::from_micros(s.2),
-                cmd: s.3,
-                address: s.4,
-                client_name: s.5,
+/// Wrap a field in double quotes, escaping any embedded quotes, so values
+/// containing commas (e.g. a `CLIENT SETNAME` with a comma) never break the
+/// CSV column layout.
+fn csv_quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// A slowlog entry paired with the instance it came from, for JSONL output.
+#[derive(Serialize)]
+struct Tagged<'a> {
+    instance: &'a str,
+    #[serde(flatten)]
+    slowlog: &'a Slowlog,
+}
+
+/// Render a single slowlog entry to stdout in the requested format, tagged with
+/// its originating instance. The shared `sink` lock keeps records from
+/// different instances from interleaving mid-line.
+fn emit(slowlog: &Slowlog, format: &Format, instance: &str, sink: &Mutex<()>) {
+    let _guard = sink.lock().unwrap();
+    match format {
+        Format::Text => {
+            let dt = match DateTime::from_timestamp(slowlog.timestamp as i64, 0) {
+                Some(dt) => dt,
+                None => return,
+            };
+            println!(
+                "[{:?}] instance={}, id={}, time={:.1}[ms], cmd='{:?}', address={}, name={}",
+                dt,
+                instance,
+                slowlog.id,
+                slowlog.exec_time.as_secs_f64() * 1000.0,
+                slowlog.cmd,
+                slowlog.address,
+                slowlog.client_name
+            );
+        }
+        Format::Json => {
+            let tagged = Tagged { instance, slowlog };
+            if let Ok(line) = serde_json::to_string(&tagged) {
+                println!("{}", line);
             }
-        } else {
-            let s: (u64, u64, u64, Vec<String>) =
-                FromRedisValue::from_redis_value(raw_slowlog).unwrap();
-            RedisSlowlog {
-                id: s.0,
-                timestamp: s.1,
-                exec_time: Duration::from_micros(s.2),
-                cmd: s.3,
-                ..RedisSlowlog::default()
+        }
+        Format::Csv => {
+            let cmd = slowlog.cmd.join(" ");
+            println!(
+                "{},{},{},{},{},{},{}",
+                csv_quote(instance),
+                slowlog.id,
+                iso8601(slowlog.timestamp),
+                slowlog.exec_time.as_micros(),
+                csv_quote(&cmd),
+                csv_quote(&slowlog.address),
+                csv_quote(&slowlog.client_name)
+            );
+        }
+    }
+}
+
+/// Spread reconnect attempts out by adding up to ~25% jitter, using the wall
+/// clock as a cheap entropy source so we avoid a new dependency.
+fn with_jitter(delay_ms: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0) as u64;
+    let span = (delay_ms / 4).max(1);
+    delay_ms + (nanos % span)
+}
+
+/// Open a connection to `host`, retrying with exponential backoff so a
+/// transient network blip or a server restart never takes the monitor down.
+/// In follow mode it retries forever; in one-shot mode it exits the process
+/// with status 1 after `CONNECT_MAX_ATTEMPTS` failed attempts.
+fn connect(config: &Config, host: &str) -> redis::Connection {
+    let client = redis::Client::open(config.url_for(host)).expect("fail connect redis");
+    let mut backoff = BACKOFF_INITIAL_MS;
+    let mut attempt: u32 = 0;
+    loop {
+        match client.get_connection_with_timeout(Duration::from_millis(config.timeout)) {
+            Ok(conn) => return conn,
+            Err(e) => {
+                attempt += 1;
+                // Follow mode retries indefinitely; one-shot bails out so the
+                // process exits non-zero rather than blocking a cron job.
+                if !config.follow && attempt >= CONNECT_MAX_ATTEMPTS {
+                    eprintln!(
+                        "[{}] connection error: {}; giving up after {} attempts",
+                        host, e, attempt
+                    );
+                    std::process::exit(1);
+                }
+                let delay = with_jitter(backoff);
+                eprintln!("[{}] connection error: {}; retrying in {}ms", host, e, delay);
+                std::thread::sleep(Duration::from_millis(delay));
+                backoff = (backoff * 2).min(BACKOFF_CAP_MS);
             }
-        };
-        if IGNORE_COMMANDS.contains(&slowlog.cmd[0].to_uppercase().as_str()) {
-            continue;
         }
-        slowlogs.push(slowlog);
     }
-    slowlogs
 }
 
-fn main() {
-    let client = redis::Client::open("redis://127.0.0.1").expect("fail connect redis");
-    let conn = client
-        .get_connection()
-        .expect("fail to get redis connection");
-    let redis_version = get_version(&conn);
-    match redis_version {
-        Some(ref v) => println!("redis version: {}.{}.{}", v.major, v.minor, v.patch),
-        None => println!("redis version: unknown"),
+/// Print the server version banner for `instance`, tolerating a failed probe.
+fn report_version(conn: &mut redis::Connection, instance: &str, sink: &Mutex<()>) {
+    let _guard = sink.lock().unwrap();
+    match server_version(conn) {
+        Ok(Some(v)) => println!("[{}] redis version: {}.{}.{}", instance, v.major, v.minor, v.patch),
+        Ok(None) => println!("[{}] redis version: unknown", instance),
+        Err(e) => eprintln!("[{}] failed to read redis version: {}", instance, e),
     }
+}
 
-    let redis_version_major = match redis_version {
-        Some(v) => v.major,
-        None => 0,
-    };
+/// Poll a single instance, maintaining its own version detection and dedup
+/// state and emitting through the shared sink.
+fn run_worker(config: Arc<Config>, host: String, sink: Arc<Mutex<()>>) {
+    let mut conn = connect(&config, &host);
+    report_version(&mut conn, &host, &sink);
 
-    let mut all_slowlogs: HashMap<u64, RedisSlowlog> = HashMap::new();
+    let mut seen = SeenIds::new(config.lru_capacity);
     loop {
-        let slowlogs = get_slowlogs(&conn, 100, redis_version_major);
-        for slowlog in slowlogs {
-            if !all_slowlogs.contains_key(&slowlog.id) {
-                let ndt = NaiveDateTime::from_timestamp_opt(slowlog.timestamp as i64, 0);
-                if ndt.is_none() {
-                    continue;
+        match fetch_slowlogs(&mut conn, config.count) {
+            Ok(slowlogs) => {
+                for slowlog in slowlogs {
+                    if !seen.mark(slowlog.id) {
+                        emit(&slowlog, &config.format, &host, &sink);
+                    }
                 }
-                let dt = DateTime::<Utc>::from_utc(ndt.unwrap(), Utc);
-                println!(
-                    "[{:?}] id={}, time={:.1}[ms], cmd='{:?}', address={}, name={}",
-                    dt,
-                    slowlog.id,
-                    slowlog.exec_time.subsec_nanos() as f64 * 1e-6,
-                    slowlog.cmd,
-                    slowlog.address,
-                    slowlog.client_name
-                );
-                all_slowlogs.insert(slowlog.id, slowlog);
+                if !config.follow {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(config.interval));
+            }
+            Err(e) => {
+                eprintln!("[{}] slowlog error: {}; reconnecting", host, e);
+                conn = connect(&config, &host);
+                report_version(&mut conn, &host, &sink);
             }
         }
-        std::thread::sleep(Duration::from_millis(5000));
+    }
+}
+
+fn main() {
+    let config = Arc::new(Config::parse());
+
+    if let Format::Csv = config.format {
+        println!("instance,id,timestamp,exec_time,cmd,address,client_name");
+    }
+
+    let sink = Arc::new(Mutex::new(()));
+    let mut workers = vec![];
+    for host in config.hosts.clone() {
+        let config = Arc::clone(&config);
+        let sink = Arc::clone(&sink);
+        workers.push(std::thread::spawn(move || run_worker(config, host, sink)));
+    }
+    for worker in workers {
+        let _ = worker.join();
     }
 }